@@ -0,0 +1,92 @@
+//! Reusable interrupt-to-stream channel.
+//!
+//! `keyboard` hand-rolled this pattern for scancodes: a lazily allocated
+//! `ArrayQueue` behind a `OnceCell` plus an `AtomicWaker`, a producer side
+//! safe to call from an interrupt handler, and a `Stream` consumer side
+//! that does the fast-path pop / register-waker / re-pop dance to close
+//! the race between a push and a poll. This module extracts that so a new
+//! interrupt source (timer ticks, serial RX, mouse packets) can get an
+//! async stream without duplicating the `OnceCell`+`AtomicWaker` boilerplate
+//! -- callers still declare their own statics (so each channel gets its own
+//! storage), they just stop reimplementing the push/wake/poll dance.
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+
+use crate::println;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Pushes `item` onto `queue`, waking whatever task is registered on
+/// `waker`, if any.
+///
+/// Safe to call from interrupt context: never blocks or allocates. Logs
+/// instead of panicking if `queue` hasn't been initialized yet or is full
+/// -- dropping one interrupt's worth of data beats panicking in an ISR.
+pub(crate) fn try_send<T>(queue: &OnceCell<ArrayQueue<T>>, waker: &AtomicWaker, name: &str, item: T) {
+    if let Ok(queue) = queue.try_get() {
+        if queue.push(item).is_err() {
+            println!("WARNING: {} queue full; dropping item", name);
+        } else {
+            waker.wake(); // this calls the parent task's .wake
+        }
+    } else {
+        println!("WARNING: {} queue uninitialized", name);
+    }
+}
+
+/// The `Stream` half of an interrupt-to-stream channel.
+///
+/// Wraps a `'static` queue/waker pair (declared by the caller, one per
+/// channel) and implements the pop/register/re-pop dance that closes the
+/// push-vs-poll race. Construct exactly once per queue, the same way
+/// `ScancodeStream::new` is only ever called once.
+pub(crate) struct IrqReceiver<T: 'static> {
+    queue: &'static OnceCell<ArrayQueue<T>>,
+    waker: &'static AtomicWaker,
+}
+
+impl<T: 'static> IrqReceiver<T> {
+    pub(crate) fn new(
+        queue: &'static OnceCell<ArrayQueue<T>>,
+        waker: &'static AtomicWaker,
+        capacity: usize,
+    ) -> Self {
+        queue
+            .try_init_once(|| ArrayQueue::new(capacity))
+            .expect("IrqReceiver::new should only be called once per queue");
+        IrqReceiver { queue, waker }
+    }
+}
+
+impl<T: 'static> Stream for IrqReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let queue = self
+            .queue
+            .try_get()
+            .expect("IrqReceiver queue not initialized");
+
+        // fast path
+        if let Ok(item) = queue.pop() {
+            return Poll::Ready(Some(item));
+        }
+
+        // overwrite our waker with the parent task's waker
+        // -- it's our duty if we return Poll::Pending
+        self.waker.register(cx.waker());
+        match queue.pop() {
+            Ok(item) => {
+                // if the interrupt pushed since we last checked
+                self.waker.take(); // nvm, we don't need that waker
+                Poll::Ready(Some(item))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
@@ -1,37 +1,68 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc};
+use super::{JoinHandle, Task, TaskId};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+use core::cell::Cell;
 use core::task::Waker;
 use crossbeam_queue::ArrayQueue;
 
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    // tasks ready to be polled right now
+    run_queue: VecDeque<Task>,
+    // tasks parked on Poll::Pending, keyed by id so a wake can find them
+    wait_queue: BTreeMap<TaskId, Task>,
+    // ids pushed by wakers -- interrupt-safe, so this is the only structure
+    // that an interrupt handler is allowed to touch
+    wake_queue: Arc<ArrayQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
+    // guards block_on against reentrancy -- see block_on below
+    in_block_on: Cell<bool>,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
-            tasks: BTreeMap::new(),
-            // wakers push IDs of awoken tasks onto the queue
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            run_queue: VecDeque::new(),
+            wait_queue: BTreeMap::new(),
             // no allocations here, because it's pushed to from an interrupt handler -- others are not
-            // 100 is "small" for concurrent tasks, could be handled by distinct threads in Linux
+            // 100 is "small" for concurrent tasks, but now only bounds the count of *parked* tasks
+            wake_queue: Arc::new(ArrayQueue::new(100)),
             waker_cache: BTreeMap::new(),
+            in_block_on: Cell::new(false),
         }
     }
 }
 
 impl Executor {
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
+    /// Spawns `future` onto the run queue and returns a `JoinHandle` that
+    /// can be `.await`ed for its output or used to `cancel` the task.
+    pub fn spawn<T: 'static>(&mut self, future: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+        let (task, handle) = Task::with_join_handle(future);
+        // newly spawned tasks are ready by definition -- straight into the
+        // run queue, no need to round-trip through the (bounded) wake queue
+        self.run_queue.push_back(task);
+        handle
+    }
+
+    /// Removes `task_id` from whichever of the run/wait queues it's in and
+    /// drops its future, without ever polling it to completion. Notifies
+    /// the task's `JoinHandle`, if any, so an awaiter sees `Cancelled`
+    /// instead of waiting forever. A no-op if the task already finished
+    /// (or `task_id` never existed).
+    pub fn cancel(&mut self, task_id: TaskId) {
+        self.waker_cache.remove(&task_id);
+
+        if let Some(task) = self.wait_queue.remove(&task_id) {
+            task.notify_cancelled();
+            return;
+        }
+
+        if let Some(index) = self.run_queue.iter().position(|task| task.id == task_id) {
+            if let Some(task) = self.run_queue.remove(index) {
+                task.notify_cancelled();
+            }
         }
-        // interesting that this is a panic rather than an error --
-        //  i guess because there is no return type?
-        self.task_queue.push(task_id).expect("queue full");
-        // pushing to the queue ensures that the future _will_ be polled
     }
 }
 
@@ -39,43 +70,68 @@ use core::task::{Context, Poll};
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    wake_queue: Arc<ArrayQueue<TaskId>>,
+    is_queued: Arc<AtomicBool>,
 }
 impl TaskWaker {
     fn wake_task(&self) {
-        // no mut self because ArrayQueue is atomic
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        // no mut self because ArrayQueue and AtomicBool are atomic
+        //
+        // only the wake that flips is_queued from false to true actually
+        // pushes -- later wakes before the next poll see it's already
+        // true and are no-ops, so a task woken repeatedly while parked
+        // still only occupies one slot in wake_queue
+        if !self.is_queued.swap(true, Ordering::AcqRel) {
+            self.wake_queue.push(self.task_id).expect("wake_queue full");
+        }
     }
 }
 
 impl Executor {
+    // moves the Task belonging to each woken id from wait_queue into run_queue
+    fn drain_wake_queue(&mut self) {
+        while let Ok(task_id) = self.wake_queue.pop() {
+            if let Some(task) = self.wait_queue.remove(&task_id) {
+                self.run_queue.push_back(task);
+            }
+            // if the id isn't in wait_queue, the task already finished
+            // (or was never parked) -- nothing to do
+        }
+    }
+
     fn run_ready_tasks(&mut self) {
         // destructure `self` to avoid borrow checker errors
         let Self {
-            tasks,
-            task_queue,
+            run_queue,
+            wait_queue,
+            wake_queue,
             waker_cache,
+            ..
         } = self;
 
-        while let Ok(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
+        while let Some(mut task) = run_queue.pop_front() {
+            let task_id = task.id;
             let waker = waker_cache.entry(task_id).or_insert_with(|| {
                 TaskWaker::new(
                     task_id,
-                    task_queue.clone(), // waker has access to queue so we can push on wake
-                ) // note: task_queue is an Arc, so .clone only increments the ref count
+                    wake_queue.clone(), // waker has access to queue so we can push on wake
+                    task.is_queued.clone(),
+                ) // note: wake_queue and is_queued are Arcs, so .clone only bumps a refcount
             });
+            // clear the flag before polling, not after, so a wake that
+            // arrives mid-poll still re-enqueues the task instead of being
+            // swallowed by a flag this poll is about to reset anyway
+            task.is_queued.store(false, Ordering::Release);
             let mut context = Context::from_waker(waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    tasks.remove(&task_id);
+                    // task done -> drop it and its cached waker
                     waker_cache.remove(&task_id);
                 }
-                Poll::Pending => {}
+                Poll::Pending => {
+                    // parked until a wake moves it back into run_queue
+                    wait_queue.insert(task_id, task);
+                }
             }
         }
     }
@@ -94,10 +150,15 @@ impl Wake for TaskWaker {
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(
+        task_id: TaskId,
+        wake_queue: Arc<ArrayQueue<TaskId>>,
+        is_queued: Arc<AtomicBool>,
+    ) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
-            task_queue,
+            wake_queue,
+            is_queued,
         }))
     }
 }
@@ -105,6 +166,7 @@ impl TaskWaker {
 impl Executor {
     pub fn run(&mut self) -> ! {
         loop {
+            self.drain_wake_queue();
             self.run_ready_tasks();
             self.sleep_if_idle(); // prevent busy loop
         }
@@ -113,11 +175,83 @@ impl Executor {
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
-        interrupts::disable(); // prevent race condition with interrupts that mutate queue
-        if self.task_queue.is_empty() {
+        interrupts::disable(); // prevent race condition with interrupts that mutate the wake queue
+        if self.wake_queue.is_empty() {
+            // run_queue is always drained by run_ready_tasks before we get here,
+            // so the wake queue being empty means there's truly nothing to do
             enable_and_hlt(); // re-enable interrupts and issue a halt, atomically
         } else {
             interrupts::enable(); // re-enable interrupts and return
         }
     }
 }
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+impl Executor {
+    /// Runs a single future to completion and returns its output, without
+    /// spawning a `Task` or touching the `ArrayQueue`.
+    ///
+    /// This is for kernel init code that needs a concrete result -- e.g.
+    /// reading one line from the keyboard, or awaiting a disk read --
+    /// rather than firing off a `Task` and moving on. Because only one
+    /// future is ever in flight here, the waker doesn't need the queue:
+    /// it's just an `AtomicBool` that `wake`/`wake_by_ref` set, and the
+    /// halt loop clears it after each poll.
+    pub fn block_on<T>(&self, future: impl Future<Output = T>) -> T {
+        if self.in_block_on.replace(true) {
+            // the halt-based waker below can't distinguish wakes meant for
+            // an outer call from wakes meant for an inner one
+            panic!("Executor::block_on does not support nested calls");
+        }
+
+        let mut future = Box::pin(future);
+        let ready = AtomicBool::new(false);
+        let waker = block_on_waker(&ready);
+        let mut context = Context::from_waker(&waker);
+
+        let output = loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                break output;
+            }
+
+            use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+            interrupts::disable(); // prevent race condition with a wake between the poll and the hlt
+            if ready.load(Ordering::Relaxed) {
+                interrupts::enable(); // already woken since the last poll -- go again without halting
+            } else {
+                enable_and_hlt(); // re-enable interrupts and issue a halt, atomically
+            }
+            ready.store(false, Ordering::Relaxed);
+        };
+
+        self.in_block_on.set(false);
+        output
+    }
+}
+
+use core::task::{RawWaker, RawWakerVTable};
+
+fn block_on_raw_waker(ready: *const AtomicBool) -> RawWaker {
+    unsafe fn clone(ready: *const ()) -> RawWaker {
+        block_on_raw_waker(ready as *const AtomicBool)
+    }
+    unsafe fn wake(ready: *const ()) {
+        wake_by_ref(ready)
+    }
+    unsafe fn wake_by_ref(ready: *const ()) {
+        (*(ready as *const AtomicBool)).store(true, Ordering::Relaxed);
+    }
+    unsafe fn drop(_: *const ()) {} // ready is borrowed, not owned -- nothing to do
+
+    let vtable = &RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+    RawWaker::new(ready as *const (), vtable)
+}
+
+fn block_on_waker(ready: &AtomicBool) -> Waker {
+    unsafe { Waker::from_raw(block_on_raw_waker(ready as *const AtomicBool)) }
+}
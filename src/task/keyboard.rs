@@ -7,36 +7,27 @@ use crossbeam_queue::ArrayQueue;
 static SCANCODE_QUEUE: // a statically-sized queue for holding scancodes
     OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 
-use crate::println;
-
 use futures_util::task::AtomicWaker;
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+use super::channel;
+
 /// Called by the keyboard interrupt handler
 /// So it must not block or allocate.
 /// Instead, it spins -- I think around a compare-and-swap instruction?
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if queue.push(scancode).is_err() {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake(); // this calls the parent task's .wake
-        }
-    } else {
-        println!("WARNING: scancode queue uninitialized");
-    }
+    channel::try_send(&SCANCODE_QUEUE, &WAKER, "scancode", scancode);
 }
 
 pub struct ScancodeStream {
-    _private: (),
+    inner: channel::IrqReceiver<u8>,
 }
 
 impl ScancodeStream {
     pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
-        ScancodeStream { _private: () }
+        ScancodeStream {
+            inner: channel::IrqReceiver::new(&SCANCODE_QUEUE, &WAKER, 100),
+        }
     }
 }
 
@@ -56,26 +47,7 @@ impl Stream for ScancodeStream {
     type Item = u8;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
-        let queue = SCANCODE_QUEUE
-            .try_get()
-            .expect("scancode queue not initialized");
-
-        // fast path
-        if let Ok(scancode) = queue.pop() {
-            return Poll::Ready(Some(scancode));
-        }
-
-        // overwrite our waker with the parent task's waker
-        // -- it's our duty if we return Poll::Pending
-        WAKER.register(cx.waker());
-        match queue.pop() {
-            Ok(scancode) => {
-                // if interrupt pushed since we last checked
-                WAKER.take(); // nvm, we don't need that waker
-                Poll::Ready(Some(scancode))
-            }
-            Err(crossbeam_queue::PopError) => Poll::Pending,
-        }
+        Pin::new(&mut self.get_mut().inner).poll_next(cx)
     }
 }
 
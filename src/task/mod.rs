@@ -1,6 +1,9 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicBool;
 use core::{future::Future, pin::Pin};
 
+pub(crate) mod channel;
 pub mod executor;
 pub mod keyboard;
 pub mod simple_executor;
@@ -13,6 +16,15 @@ pub struct Task {
     // Box: Store those futures on the heap
     // Pin: Prevent &mut refs to futures so memory location stable
     future: Pin<Box<dyn Future<Output = ()>>>,
+    // true while this task's id is already scheduled to be polled
+    // (either sitting in the executor's run queue, or already pushed onto
+    // its wake queue), so a waker firing more than once before the next
+    // poll enqueues the id at most once
+    is_queued: Arc<AtomicBool>,
+    // run once by Executor::cancel, right before the future is dropped, so
+    // a JoinHandle awaiting this task observes cancellation instead of
+    // hanging forever; a no-op for tasks with no JoinHandle
+    on_cancel: Box<dyn FnOnce()>,
 }
 
 impl Task {
@@ -20,6 +32,10 @@ impl Task {
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
+            // a freshly spawned task goes straight into the run queue, so
+            // it already counts as "queued"
+            is_queued: Arc::new(AtomicBool::new(true)),
+            on_cancel: Box::new(|| {}),
         }
     }
 }
@@ -30,10 +46,15 @@ impl Task {
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
+
+    // called by Executor::cancel instead of polling to completion
+    fn notify_cancelled(self) {
+        (self.on_cancel)();
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 use core::sync::atomic::{AtomicU64, Ordering};
 
@@ -43,3 +64,140 @@ impl TaskId {
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
 }
+
+use spin::Mutex;
+
+/// Where a spawned task's output lands once it is produced (or a flag
+/// recording that the task was cancelled first). Shared between a
+/// `JoinHandle` and the `JoinAdapter` wrapping the task it belongs to.
+struct JoinSlot<T> {
+    output: Mutex<Option<T>>,
+    // set by Task::notify_cancelled when the task is cancelled before it
+    // ever produces an output
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl<T> JoinSlot<T> {
+    fn new() -> Self {
+        JoinSlot {
+            output: Mutex::new(None),
+            cancelled: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+}
+
+/// Wraps a `Future<Output = T>` so the executor -- which only ever polls
+/// `Future<Output = ()>` tasks -- can drive it. On completion, stores the
+/// output in the shared `JoinSlot` and wakes whatever is `.await`ing the
+/// matching `JoinHandle`, instead of handing the output back through
+/// `poll`.
+struct JoinAdapter<F: Future> {
+    future: F,
+    slot: Arc<JoinSlot<F::Output>>,
+}
+
+impl<F: Future> Future for JoinAdapter<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // SAFETY: this is a standard pin projection -- we never move `future`
+        // or `slot` out of `self`, only hand out a pinned reference to `future`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        match future.poll(cx) {
+            Poll::Ready(output) => {
+                *this.slot.output.lock() = Some(output);
+                this.slot.waker.wake();
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Task {
+    /// Like `new`, but for futures that produce a value the caller wants
+    /// back. Used by `Executor::spawn`, which hands the `JoinHandle` half
+    /// to the caller and keeps only the type-erased `Task` half for itself.
+    pub(crate) fn with_join_handle<T: 'static>(
+        future: impl Future<Output = T> + 'static,
+    ) -> (Task, JoinHandle<T>) {
+        let slot = Arc::new(JoinSlot::new());
+        let cancel_slot = slot.clone();
+        let task = Task {
+            id: TaskId::new(),
+            future: Box::pin(JoinAdapter {
+                future,
+                slot: slot.clone(),
+            }),
+            is_queued: Arc::new(AtomicBool::new(true)),
+            on_cancel: Box::new(move || {
+                cancel_slot.cancelled.store(true, Ordering::Release);
+                cancel_slot.waker.wake();
+            }),
+        };
+        let handle = JoinHandle {
+            task_id: task.id,
+            slot,
+        };
+        (task, handle)
+    }
+}
+
+use futures_util::task::AtomicWaker;
+
+/// A handle to a spawned task that can be `.await`ed for its output, or
+/// cancelled before it produces one. `.await`ing a cancelled task (whether
+/// cancelled through this handle or through `Executor::cancel` directly)
+/// resolves to `Err(Cancelled)` rather than hanging forever.
+pub struct JoinHandle<T> {
+    task_id: TaskId,
+    slot: Arc<JoinSlot<T>>,
+}
+
+/// Returned by a `JoinHandle` whose task was cancelled before it produced
+/// an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl<T> JoinHandle<T> {
+    pub fn task_id(&self) -> TaskId {
+        self.task_id
+    }
+
+    /// Cancels the underlying task via `executor`, dropping its future
+    /// without it ever producing an output.
+    pub fn cancel(self, executor: &mut executor::Executor) {
+        executor.cancel(self.task_id);
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Cancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Cancelled>> {
+        // fast path
+        if let Some(output) = self.slot.output.lock().take() {
+            return Poll::Ready(Ok(output));
+        }
+        if self.slot.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(Err(Cancelled));
+        }
+
+        // overwrite our waker with the parent task's waker
+        // -- it's our duty if we return Poll::Pending
+        self.slot.waker.register(cx.waker());
+        if let Some(output) = self.slot.output.lock().take() {
+            // the task completed since we last checked
+            self.slot.waker.take(); // nvm, we don't need that waker
+            return Poll::Ready(Ok(output));
+        }
+        if self.slot.cancelled.load(Ordering::Acquire) {
+            self.slot.waker.take();
+            return Poll::Ready(Err(Cancelled));
+        }
+        Poll::Pending
+    }
+}